@@ -1,5 +1,7 @@
 mod ted;
-use rowan::{Language, SyntaxElement, SyntaxNode};
+use rowan::{
+    GreenNodeBuilder, Language, NodeOrToken, SyntaxElement, SyntaxNode, SyntaxToken, TextRange, TextSize,
+};
 
 use itertools::Itertools;
 
@@ -17,6 +19,233 @@ pub struct TreeDiff<L: Language> {
     pub insertions: Vec<(TreeDiffInsertPos<L>, Vec<SyntaxElement<L>>)>,
 }
 
+fn element_text<L: Language>(elt: &SyntaxElement<L>) -> String {
+    match elt {
+        NodeOrToken::Node(node) => node.to_string(),
+        NodeOrToken::Token(token) => token.text().to_string(),
+    }
+}
+
+/// Finds the smallest sub-range of `from`'s text that differs from `to`'s, by trimming their
+/// common prefix and suffix, and returns it together with its replacement text. Returns `None`
+/// if the two tokens have identical text.
+fn char_level_edit<L: Language>(from: &SyntaxToken<L>, to: &SyntaxToken<L>) -> Option<(TextRange, String)> {
+    let old = from.text();
+    let new = to.text();
+    if old == new {
+        return None;
+    }
+
+    let prefix_len = old
+        .char_indices()
+        .zip(new.chars())
+        .take_while(|((_, a), b)| a == b)
+        .last()
+        .map(|((i, c), _)| i + c.len_utf8())
+        .unwrap_or(0);
+
+    let suffix_len = old[prefix_len..]
+        .chars()
+        .rev()
+        .zip(new[prefix_len..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum::<usize>();
+
+    let start = from.text_range().start();
+    let range = TextRange::new(
+        start + TextSize::from(prefix_len as u32),
+        start + TextSize::from((old.len() - suffix_len) as u32),
+    );
+    Some((range, new[prefix_len..new.len() - suffix_len].to_string()))
+}
+
+impl<L: Language> TreeDiff<L> {
+    /// Lowers this structural diff into offset-based text edits, feeding each one to `acc`.
+    ///
+    /// Replacements become a replace over the replaced element's range, deletions a replace
+    /// with an empty string, and insertions an empty-range replace at the anchor offset implied
+    /// by their `TreeDiffInsertPos` (`After` anchors at the end of the preceding element,
+    /// `AsFirstChild` anchors at the start of the parent).
+    ///
+    /// When `char_level` is set, a replacement between two tokens is additionally narrowed down
+    /// to the minimal sub-range that actually differs (trimming their common prefix/suffix)
+    /// instead of replacing the whole token; this keeps diffs minimal for things like
+    /// identifier or string-literal tweaks. Structural-only consumers can pass `false` to get
+    /// the coarser, token-at-a-time behavior unchanged.
+    pub fn into_text_edit(&self, acc: &mut dyn FnMut(TextRange, String), char_level: bool) {
+        for (from, to) in &self.replacements {
+            match (char_level, from, to) {
+                (true, NodeOrToken::Token(from_tok), NodeOrToken::Token(to_tok)) => {
+                    if let Some((range, text)) = char_level_edit(from_tok, to_tok) {
+                        acc(range, text);
+                    }
+                }
+                _ => acc(from.text_range(), element_text(to)),
+            }
+        }
+        for deletion in &self.deletions {
+            acc(deletion.text_range(), String::new());
+        }
+        for (pos, elements) in &self.insertions {
+            let offset = match pos {
+                TreeDiffInsertPos::After(it) => it.text_range().end(),
+                TreeDiffInsertPos::AsFirstChild(parent) => parent.text_range().start(),
+            };
+            let text = elements.iter().map(element_text).collect::<String>();
+            acc(TextRange::empty(offset), text);
+        }
+    }
+
+    /// Convenience wrapper around [`TreeDiff::into_text_edit`] that collects the edits into a
+    /// `Vec` instead of requiring a callback.
+    pub fn into_text_edits(&self, char_level: bool) -> Vec<(TextRange, String)> {
+        let mut edits = Vec::new();
+        self.into_text_edit(&mut |range, text| edits.push((range, text)), char_level);
+        edits
+    }
+
+    /// Converts this diff into a [`TreeDiffPtr`], whose anchors into `from` are recorded
+    /// positionally instead of as live handles, so it survives `from` being mutated.
+    pub fn to_ptr(&self) -> TreeDiffPtr<L> {
+        TreeDiffPtr {
+            replacements: self
+                .replacements
+                .iter()
+                .map(|(from, to)| (SyntaxElementPtr::new(from), to.clone()))
+                .collect(),
+            deletions: self.deletions.iter().map(SyntaxElementPtr::new).collect(),
+            insertions: self
+                .insertions
+                .iter()
+                .map(|(pos, elements)| {
+                    let pos = match pos {
+                        TreeDiffInsertPos::After(it) => TreeDiffInsertPosPtr::After(SyntaxElementPtr::new(it)),
+                        TreeDiffInsertPos::AsFirstChild(parent) => {
+                            TreeDiffInsertPosPtr::AsFirstChild(SyntaxElementPtr::new(parent))
+                        }
+                    };
+                    (pos, elements.clone())
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A stable stand-in for a [`SyntaxElement`], recorded as its kind plus [`TextRange`] rather
+/// than a live handle into a specific tree (analogous to rust-analyzer's `SyntaxNodePtr`).
+/// Resolve it back against a (re-)parsed tree of the same source with [`SyntaxElementPtr::resolve`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntaxElementPtr<L: Language> {
+    kind: L::Kind,
+    range: TextRange,
+}
+
+impl<L: Language> SyntaxElementPtr<L> {
+    pub fn new(elt: &SyntaxElement<L>) -> Self {
+        SyntaxElementPtr {
+            kind: elt.kind(),
+            range: elt.text_range(),
+        }
+    }
+
+    /// Re-hydrates this pointer against `root`, which must parse the same source the pointer
+    /// was recorded against.
+    ///
+    /// [`covering_element`](SyntaxNode::covering_element) returns the *deepest* element whose
+    /// range contains `self.range`, which isn't necessarily the element this pointer was
+    /// recorded against: when a node's range coincides exactly with one of its descendants'
+    /// (e.g. a wrapper node around its sole child token), `covering_element` keeps descending
+    /// past the node and into that descendant. So this walks back up through same-range
+    /// ancestors looking for a kind match, rather than trusting the first (deepest) hit.
+    pub fn resolve(&self, root: &SyntaxNode<L>) -> SyntaxElement<L> {
+        let mut elt = root.covering_element(self.range);
+        while elt.kind() != self.kind || elt.text_range() != self.range {
+            match elt.parent() {
+                Some(parent) if parent.text_range() == self.range => elt = NodeOrToken::Node(parent),
+                _ => panic!(
+                    "SyntaxElementPtr::resolve: no element of kind {:?} found at {:?} in `root` \
+                     (`root` likely does not match the source this pointer was recorded against)",
+                    self.kind, self.range
+                ),
+            }
+        }
+        elt
+    }
+}
+
+#[derive(Debug)]
+pub enum TreeDiffInsertPosPtr<L: Language> {
+    After(SyntaxElementPtr<L>),
+    AsFirstChild(SyntaxElementPtr<L>),
+}
+
+/// A [`TreeDiff`] whose `from`-anchored positions (replacement sites, deletions, insertion
+/// anchors) are recorded as [`SyntaxElementPtr`]s instead of live elements. Unlike `TreeDiff`,
+/// these anchors stay valid after `from` is mutated, and the diff can be re-applied against a
+/// freshly parsed copy of the same source via [`TreeDiffPtr::resolve`]. The replacement and
+/// inserted elements are still held as live [`SyntaxElement`]s of the `to` tree, so a
+/// `TreeDiffPtr` is not `Send` and has no serialized form.
+#[derive(Debug)]
+pub struct TreeDiffPtr<L: Language> {
+    pub replacements: Vec<(SyntaxElementPtr<L>, SyntaxElement<L>)>,
+    pub deletions: Vec<SyntaxElementPtr<L>>,
+    pub insertions: Vec<(TreeDiffInsertPosPtr<L>, Vec<SyntaxElement<L>>)>,
+}
+
+impl<L: Language> TreeDiffPtr<L> {
+    /// Re-hydrates every pointer in this diff against `root`, producing a [`TreeDiff`] usable
+    /// with [`apply`].
+    pub fn resolve(&self, root: &SyntaxNode<L>) -> TreeDiff<L> {
+        TreeDiff {
+            replacements: self
+                .replacements
+                .iter()
+                .map(|(from, to)| (from.resolve(root), to.clone()))
+                .collect(),
+            deletions: self.deletions.iter().map(|ptr| ptr.resolve(root)).collect(),
+            insertions: self
+                .insertions
+                .iter()
+                .map(|(pos, elements)| {
+                    let pos = match pos {
+                        TreeDiffInsertPosPtr::After(ptr) => TreeDiffInsertPos::After(ptr.resolve(root)),
+                        TreeDiffInsertPosPtr::AsFirstChild(ptr) => {
+                            TreeDiffInsertPos::AsFirstChild(ptr.resolve(root))
+                        }
+                    };
+                    (pos, elements.clone())
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Cost model used to weigh nodes and tokens against each other while searching for a diff.
+///
+/// A single-character token rename and the deletion of a large subtree both cost `1` under
+/// `tree_edit_distance`'s default weighing, which biases the algorithm towards awkward
+/// restructurings instead of fine-grained in-place edits. `DiffConfig` lets a caller correct for
+/// that by supplying its own weights.
+pub struct DiffConfig<L: Language> {
+    /// Weight of inserting or removing a node, given the size of its subtree (the node itself
+    /// plus all of its descendants). Defaults to that size unchanged, so deleting a large
+    /// subtree costs proportionally more than deleting a single token.
+    pub node_weight: Box<dyn Fn(L::Kind, u32) -> u32>,
+    /// Weight of inserting, removing, or substituting a token, given its text. Defaults to a
+    /// flat `1`.
+    pub token_weight: Box<dyn Fn(&str) -> u32>,
+}
+
+impl<L: Language> Default for DiffConfig<L> {
+    fn default() -> Self {
+        DiffConfig {
+            node_weight: Box::new(|_kind, subtree_size| subtree_size),
+            token_weight: Box::new(|_text| 1),
+        }
+    }
+}
+
 /// Finds a (potentially minimal) diff, which, applied to `from`, will result in `to`.
 ///
 /// Specifically, returns a structure that consists of a replacements, insertions and deletions
@@ -24,6 +253,15 @@ pub struct TreeDiff<L: Language> {
 ///
 /// This function tries to find a fine-grained diff.
 pub fn diff<L: Language + 'static>(from: &SyntaxNode<L>, to: &SyntaxNode<L>) -> TreeDiff<L> {
+    diff_with_config(from, to, &DiffConfig::default())
+}
+
+/// Like [`diff`], but lets the caller tune the cost model via [`DiffConfig`].
+pub fn diff_with_config<L: Language + 'static>(
+    from: &SyntaxNode<L>,
+    to: &SyntaxNode<L>,
+    config: &DiffConfig<L>,
+) -> TreeDiff<L> {
     let mut diff = TreeDiff {
         replacements: Vec::new(),
         insertions: Vec::new(),
@@ -31,7 +269,7 @@ pub fn diff<L: Language + 'static>(from: &SyntaxNode<L>, to: &SyntaxNode<L>) ->
     };
     generate_diff(
         &mut diff,
-        ted::edits(from, to),
+        ted::edits(from, to, config),
         None,
         Some(from.clone().into()).into_iter(),
         Some(to.clone().into()).into_iter(),
@@ -40,6 +278,147 @@ pub fn diff<L: Language + 'static>(from: &SyntaxNode<L>, to: &SyntaxNode<L>) ->
     diff
 }
 
+/// Returns the chain of child indices leading from the root of `elt`'s tree down to `elt`
+/// itself, e.g. `[1, 0]` means "the first child of the second child of the root".
+fn path_from_root<L: Language>(elt: &SyntaxElement<L>) -> Vec<usize> {
+    let mut indices = Vec::new();
+    let mut current = elt.clone();
+    while let Some(parent) = match &current {
+        NodeOrToken::Node(node) => node.parent(),
+        NodeOrToken::Token(token) => token.parent(),
+    } {
+        indices.push(current.index());
+        current = parent.into();
+    }
+    indices.reverse();
+    indices
+}
+
+/// Walks `path` (as produced by [`path_from_root`]) down from `root`, returning the element it
+/// points to in `root`'s own tree.
+fn resolve_path<L: Language>(root: &SyntaxNode<L>, path: &[usize]) -> SyntaxElement<L> {
+    let mut current: SyntaxElement<L> = root.clone().into();
+    for &idx in path {
+        current = current
+            .as_node()
+            .unwrap()
+            .children_with_tokens()
+            .nth(idx)
+            .unwrap();
+    }
+    current
+}
+
+/// Returns an independent, mutable copy of `elt`, detached from any tree.
+///
+/// `diff`'s replacement/insertion payload elements are still backed by the original (immutable)
+/// `to` tree, and rowan's mutation API asserts that any child passed to
+/// [`splice_children`](SyntaxNode::splice_children) is itself mutable, so they must be converted
+/// before they can be spliced into a [`clone_for_update`](SyntaxNode::clone_for_update) tree.
+fn to_mutable<L: Language>(elt: &SyntaxElement<L>) -> SyntaxElement<L> {
+    match elt {
+        NodeOrToken::Node(node) => NodeOrToken::Node(node.clone_subtree().clone_for_update()),
+        NodeOrToken::Token(token) => {
+            // Tokens have no `clone_for_update` of their own (rowan only mutates at the node
+            // level), so rebuild this one inside a throwaway node just long enough to pull a
+            // mutable copy of it back out.
+            let kind = L::kind_to_raw(token.kind());
+            let mut builder = GreenNodeBuilder::new();
+            builder.start_node(kind);
+            builder.token(kind, token.text());
+            builder.finish_node();
+            let wrapper = SyntaxNode::<L>::new_root(builder.finish()).clone_for_update();
+            let mutable_token = wrapper.first_token().unwrap();
+            mutable_token.detach();
+            NodeOrToken::Token(mutable_token)
+        }
+    }
+}
+
+/// Applies `diff` to a mutable clone of `from`, returning the resulting tree.
+///
+/// This mutates a [`clone_for_update`](SyntaxNode::clone_for_update) of `from` using rowan's
+/// `splice_children`/`detach` editing API, so `apply(from, &diff(from, to))` is structurally
+/// equivalent to `to`.
+pub fn apply<L: Language>(from: &SyntaxNode<L>, diff: &TreeDiff<L>) -> SyntaxNode<L> {
+    let root = from.clone_for_update();
+
+    // Resolve every element captured against the immutable `from` into its counterpart in the
+    // mutable clone up front. Live rowan handles keep tracking their current parent/index as
+    // siblings are spliced in around them, so this alone is enough for positions to stay correct
+    // across the splices below — the one thing it *doesn't* protect against is reading a
+    // handle's position after something else has already detached it.
+    let replacements = diff
+        .replacements
+        .iter()
+        .map(|(from_elt, to_elt)| (resolve_path(&root, &path_from_root(from_elt)), to_elt.clone()))
+        .collect_vec();
+    let insertions = diff
+        .insertions
+        .iter()
+        .map(|(pos, elements)| {
+            let pos = match pos {
+                TreeDiffInsertPos::After(it) => {
+                    TreeDiffInsertPos::After(resolve_path(&root, &path_from_root(it)))
+                }
+                TreeDiffInsertPos::AsFirstChild(parent) => {
+                    TreeDiffInsertPos::AsFirstChild(resolve_path(&root, &path_from_root(parent)))
+                }
+            };
+            (pos, elements.clone())
+        })
+        .collect_vec();
+    let deletions = diff
+        .deletions
+        .iter()
+        .map(|elt| resolve_path(&root, &path_from_root(elt)))
+        .collect_vec();
+
+    // Insertions run first, because an `After` anchor may be an element that a replacement or
+    // deletion below also targets (e.g. "replace b with x, then insert y after b") — reading
+    // that anchor's position has to happen before whatever detaches it.
+    for (pos, elements) in insertions {
+        match pos {
+            TreeDiffInsertPos::After(it) => {
+                let parent = match &it {
+                    NodeOrToken::Node(node) => node.parent().unwrap(),
+                    NodeOrToken::Token(token) => token.parent().unwrap(),
+                };
+                let idx = it.index() + 1;
+                parent.splice_children(idx..idx, elements.iter().map(to_mutable).collect_vec());
+            }
+            TreeDiffInsertPos::AsFirstChild(parent) => {
+                let parent = parent.into_node().unwrap();
+                parent.splice_children(0..0, elements.iter().map(to_mutable).collect_vec());
+            }
+        }
+    }
+
+    for (from_elt, to_elt) in replacements {
+        let parent = match &from_elt {
+            NodeOrToken::Node(node) => node.parent().unwrap(),
+            NodeOrToken::Token(token) => token.parent().unwrap(),
+        };
+        let idx = from_elt.index();
+        parent.splice_children(idx..idx + 1, vec![to_mutable(&to_elt)]);
+    }
+
+    for elt in deletions {
+        match elt {
+            NodeOrToken::Node(node) => node.detach(),
+            NodeOrToken::Token(token) => token.detach(),
+        }
+    }
+
+    root
+}
+
+/// Computes the diff between `from` and `to` and immediately applies it, returning a mutated
+/// clone of `from`.
+pub fn transform<L: Language + 'static>(from: &SyntaxNode<L>, to: &SyntaxNode<L>) -> SyntaxNode<L> {
+    apply(from, &diff(from, to))
+}
+
 #[derive(Debug, Clone)]
 pub enum TreeEdit {
     Same,
@@ -122,3 +501,286 @@ mod tests {
         assert_eq!(result, 4);
     }
 }
+
+#[cfg(test)]
+mod integration_tests {
+    use super::*;
+    use rowan::GreenNodeBuilder;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[repr(u16)]
+    enum SyntaxKind {
+        Token,
+        Root,
+        Wrapper,
+    }
+    use SyntaxKind::*;
+
+    impl From<SyntaxKind> for rowan::SyntaxKind {
+        fn from(kind: SyntaxKind) -> Self {
+            Self(kind as u16)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    enum Lang {}
+    impl Language for Lang {
+        type Kind = SyntaxKind;
+        fn kind_from_raw(raw: rowan::SyntaxKind) -> Self::Kind {
+            assert!(raw.0 <= Wrapper as u16);
+            unsafe { std::mem::transmute::<u16, SyntaxKind>(raw.0) }
+        }
+        fn kind_to_raw(kind: Self::Kind) -> rowan::SyntaxKind {
+            kind.into()
+        }
+    }
+
+    fn root_of_tokens(words: &[&str]) -> SyntaxNode<Lang> {
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(Root.into());
+        for word in words {
+            builder.token(Token.into(), word);
+        }
+        builder.finish_node();
+        SyntaxNode::new_root(builder.finish())
+    }
+
+    fn token_of(text: &str) -> SyntaxToken<Lang> {
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(Root.into());
+        builder.token(Token.into(), text);
+        builder.finish_node();
+        SyntaxNode::<Lang>::new_root(builder.finish()).first_token().unwrap()
+    }
+
+    #[test]
+    fn apply_round_trips_a_token_replacement() {
+        let from = root_of_tokens(&["a", "b", "c"]);
+        let to = root_of_tokens(&["a", "x", "c"]);
+        let result = transform::<Lang>(&from, &to);
+        assert_eq!(result.text().to_string(), to.text().to_string());
+    }
+
+    #[test]
+    fn apply_round_trips_insertions_and_deletions() {
+        let from = root_of_tokens(&["a", "b"]);
+        let to = root_of_tokens(&["a", "b", "c", "d"]);
+        let result = transform::<Lang>(&from, &to);
+        assert_eq!(result.text().to_string(), to.text().to_string());
+
+        let from = root_of_tokens(&["a", "b", "c"]);
+        let to = root_of_tokens(&["a", "c"]);
+        let result = transform::<Lang>(&from, &to);
+        assert_eq!(result.text().to_string(), to.text().to_string());
+    }
+
+    /// Applies a set of `(TextRange, String)` edits to `text`, the same way a caller of
+    /// [`TreeDiff::into_text_edits`] would: as offset-based replacements, applied back-to-front
+    /// so that earlier ranges don't shift under later ones.
+    fn apply_text_edits(text: &str, mut edits: Vec<(TextRange, String)>) -> String {
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start()));
+        let mut text = text.to_string();
+        for (range, replacement) in edits {
+            let start: usize = range.start().into();
+            let end: usize = range.end().into();
+            text.replace_range(start..end, &replacement);
+        }
+        text
+    }
+
+    #[test]
+    fn into_text_edits_lowers_a_replacement_into_a_single_offset_patch() {
+        let from = root_of_tokens(&["a", "b", "c"]);
+        let to = root_of_tokens(&["a", "x", "c"]);
+        let edits = diff(&from, &to).into_text_edits(false);
+        assert_eq!(edits.len(), 1);
+        assert_eq!(apply_text_edits(&from.text().to_string(), edits), to.text().to_string());
+    }
+
+    #[test]
+    fn into_text_edits_lowers_insertions_and_deletions_into_correct_offset_patches() {
+        let from = root_of_tokens(&["a", "b"]);
+        let to = root_of_tokens(&["a", "b", "c", "d"]);
+        let edits = diff(&from, &to).into_text_edits(false);
+        assert_eq!(apply_text_edits(&from.text().to_string(), edits), to.text().to_string());
+
+        let from = root_of_tokens(&["a", "b", "c"]);
+        let to = root_of_tokens(&["a", "c"]);
+        let edits = diff(&from, &to).into_text_edits(false);
+        assert_eq!(apply_text_edits(&from.text().to_string(), edits), to.text().to_string());
+    }
+
+    #[test]
+    fn into_text_edit_feeds_the_same_edits_into_text_edits_collects() {
+        let from = root_of_tokens(&["a", "b", "c"]);
+        let to = root_of_tokens(&["a", "x", "y", "c"]);
+        let d = diff(&from, &to);
+
+        let mut via_callback = Vec::new();
+        d.into_text_edit(&mut |range, text| via_callback.push((range, text)), false);
+
+        assert_eq!(via_callback, d.into_text_edits(false));
+    }
+
+    #[test]
+    fn char_level_edit_returns_none_for_identical_tokens() {
+        assert_eq!(char_level_edit(&token_of("hello"), &token_of("hello")), None);
+    }
+
+    #[test]
+    fn char_level_edit_trims_a_common_prefix_and_suffix() {
+        let (range, text) = char_level_edit(&token_of("foobar"), &token_of("foXYbar")).unwrap();
+        assert_eq!(range, TextRange::new(TextSize::from(2), TextSize::from(3)));
+        assert_eq!(text, "XY");
+    }
+
+    #[test]
+    fn char_level_edit_handles_no_common_affix() {
+        let (range, text) = char_level_edit(&token_of("abc"), &token_of("xyz")).unwrap();
+        assert_eq!(range, TextRange::new(TextSize::from(0), TextSize::from(3)));
+        assert_eq!(text, "xyz");
+    }
+
+    #[test]
+    fn char_level_edit_handles_an_empty_from_token() {
+        // Not a realistic token (rowan tokens are normally non-empty), but char_level_edit
+        // shouldn't panic or misbehave if handed one: an empty `old` has no prefix or suffix to
+        // trim, so this should degenerate into a plain insertion of all of `new`.
+        let (range, text) = char_level_edit(&token_of(""), &token_of("abc")).unwrap();
+        assert_eq!(range, TextRange::new(TextSize::from(0), TextSize::from(0)));
+        assert_eq!(text, "abc");
+    }
+
+    #[test]
+    fn char_level_edit_respects_multi_byte_char_boundaries() {
+        // "é" is 2 bytes, so offsets counted in chars rather than bytes would land mid-char.
+        let from = token_of("héllo");
+        let to = token_of("héxxo");
+        let (range, text) = char_level_edit(&from, &to).unwrap();
+        assert_eq!(text, "xx");
+        assert!(from.text().is_char_boundary(u32::from(range.start()) as usize));
+        assert!(from.text().is_char_boundary(u32::from(range.end()) as usize));
+        assert_eq!(&from.text()[u32::from(range.start()) as usize..u32::from(range.end()) as usize], "ll");
+    }
+
+    #[test]
+    fn into_text_edits_with_char_level_narrows_a_token_replacement() {
+        let from = root_of_tokens(&["foobar"]);
+        let to = root_of_tokens(&["foXYbar"]);
+
+        let whole_token = diff(&from, &to).into_text_edits(false);
+        assert_eq!(whole_token, vec![(from.text_range(), "foXYbar".to_string())]);
+
+        let char_level = diff(&from, &to).into_text_edits(true);
+        assert_eq!(apply_text_edits(&from.text().to_string(), char_level.clone()), to.text().to_string());
+        // Only the "o" -> "XY" sub-range should be touched, not the whole token.
+        assert_eq!(char_level, vec![(TextRange::new(TextSize::from(2), TextSize::from(3)), "XY".to_string())]);
+    }
+
+    #[test]
+    fn diff_does_not_claim_same_when_all_weights_are_zero() {
+        // Zeroing out every weight ties `Replace` against `Remove`+`Insert` in the middle span's
+        // cost model, so `tree_edit_distance` is free to pick either; the diff must still come
+        // back correct either way instead of defaulting to `Same`.
+        let from = root_of_tokens(&["a", "b", "c"]);
+        let to = root_of_tokens(&["a", "x", "y", "c"]);
+        let config = DiffConfig {
+            node_weight: Box::new(|_kind, _subtree_size| 0),
+            token_weight: Box::new(|_text| 0),
+        };
+        let diff = diff_with_config(&from, &to, &config);
+        let result = apply(&from, &diff);
+        assert_eq!(result.text().to_string(), to.text().to_string());
+    }
+
+    fn root_of_groups(groups: &[&[&str]]) -> SyntaxNode<Lang> {
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(Root.into());
+        for words in groups {
+            builder.start_node(Wrapper.into());
+            for word in *words {
+                builder.token(Token.into(), word);
+            }
+            builder.finish_node();
+        }
+        builder.finish_node();
+        SyntaxNode::new_root(builder.finish())
+    }
+
+    #[test]
+    fn diff_prunes_identical_sibling_subtrees_instead_of_touching_them() {
+        // Two Wrapper groups; only the second differs, by a single token. Pruning (matching via
+        // green-node equality before running tree-edit-distance) should leave the first group
+        // completely out of the diff and localize the second group's edit down to just the
+        // differing token, rather than e.g. replacing the whole second Wrapper node.
+        let from = root_of_groups(&[&["a", "b", "c"], &["d", "e", "f"]]);
+        let to = root_of_groups(&[&["a", "b", "c"], &["d", "x", "f"]]);
+
+        let diff = diff(&from, &to);
+        assert!(diff.deletions.is_empty());
+        assert!(diff.insertions.is_empty());
+        assert_eq!(diff.replacements.len(), 1);
+        let (from_elt, to_elt) = &diff.replacements[0];
+        assert_eq!(element_text(from_elt), "e");
+        assert_eq!(element_text(to_elt), "x");
+
+        let result = apply(&from, &diff);
+        assert_eq!(result.text().to_string(), to.text().to_string());
+    }
+
+    #[test]
+    fn node_weight_receives_a_real_element_count_not_cumulative_weight() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(Root.into());
+        builder.start_node(Wrapper.into());
+        builder.token(Token.into(), "a");
+        builder.token(Token.into(), "b");
+        builder.token(Token.into(), "c");
+        builder.finish_node();
+        builder.finish_node();
+        let from = SyntaxNode::<Lang>::new_root(builder.finish());
+        let to = root_of_tokens(&["x"]);
+
+        let seen_sizes = Rc::new(RefCell::new(Vec::new()));
+        let seen_sizes_handle = seen_sizes.clone();
+        let config = DiffConfig {
+            node_weight: Box::new(move |_kind, size| {
+                seen_sizes_handle.borrow_mut().push(size);
+                size
+            }),
+            // A non-identity token_weight is exactly the case where conflating "size" with
+            // "cumulative weight" used to go wrong: with this set, the old code would have
+            // passed 1 + 3*5 = 16 for the Wrapper node below instead of its real element count.
+            token_weight: Box::new(|_text| 5),
+        };
+        let _ = diff_with_config(&from, &to, &config);
+
+        // Wrapper has 3 token children, so its subtree holds 4 elements (itself + the 3
+        // tokens) regardless of each token's weight.
+        assert!(seen_sizes.borrow().contains(&4));
+    }
+
+    #[test]
+    fn resolve_prefers_the_originally_pointed_to_element_over_a_coincident_descendant() {
+        // A WRAPPER node whose sole child is a TOKEN with the exact same range: naively,
+        // `covering_element` would return the token instead of the wrapper.
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(Root.into());
+        builder.start_node(Wrapper.into());
+        builder.token(Token.into(), "42");
+        builder.finish_node();
+        builder.finish_node();
+        let root = SyntaxNode::<Lang>::new_root(builder.finish());
+
+        let wrapper = root.first_child().unwrap();
+        assert_eq!(wrapper.kind(), Wrapper);
+        let ptr = SyntaxElementPtr::new(&NodeOrToken::Node(wrapper.clone()));
+
+        let resolved = ptr.resolve(&root);
+        assert_eq!(resolved.kind(), Wrapper);
+        assert_eq!(resolved.text_range(), wrapper.text_range());
+    }
+}
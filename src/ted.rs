@@ -1,12 +1,15 @@
 use rowan::{Language, NodeOrToken, SyntaxElement, SyntaxNode};
 use std::mem::Discriminant;
 
-use crate::TreeEdit;
+use crate::{DiffConfig, TreeEdit};
 use itertools::Itertools;
-use tree_edit_distance::{Edit, Node, Tree};
+use tree_edit_distance::{Edit, Node};
 
+/// `TreeNode(kind, children, weight, subtree_size)`, where `subtree_size` is the plain element
+/// count of the subtree (the node itself plus all descendants) — independent of `weight`, which
+/// is caller-configurable and not generally equal to it (see [`DiffConfig::node_weight`]).
 #[derive(Debug)]
-struct TreeNode<L: Language>(TreeNodeKind<L>, Vec<TreeNode<L>>);
+struct TreeNode<L: Language>(TreeNodeKind<L>, Vec<TreeNode<L>>, u32, u32);
 
 #[derive(Debug)]
 enum TreeNodeKind<L: Language> {
@@ -16,7 +19,7 @@ enum TreeNodeKind<L: Language> {
 
 use std::mem::discriminant;
 
-impl<'n, L: Language> PartialEq for TreeNodeKind<L> {
+impl<L: Language> PartialEq for TreeNodeKind<L> {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Self::Node(l0), Self::Node(r0)) => l0 == r0,
@@ -25,6 +28,8 @@ impl<'n, L: Language> PartialEq for TreeNodeKind<L> {
         }
     }
 }
+impl<L: Language> Eq for TreeNodeKind<L> {}
+
 impl<'n, L: Language + 'static> Node<'n> for TreeNode<L> {
     type Kind = &'n TreeNodeKind<L>;
     fn kind(&'n self) -> Self::Kind {
@@ -33,40 +38,215 @@ impl<'n, L: Language + 'static> Node<'n> for TreeNode<L> {
 
     type Weight = u32;
     fn weight(&'n self) -> Self::Weight {
-        1
+        self.2
     }
-}
 
-impl<'t, L: Language + 'static> Tree<'t> for TreeNode<L> {
-    type Children = std::slice::Iter<'t, TreeNode<L>>;
-    fn children(&'t self) -> Self::Children {
-        self.1.iter()
+    type Child = &'n TreeNode<L>;
+    type Children = Vec<&'n TreeNode<L>>;
+    fn children(&'n self) -> Self::Children {
+        self.1.iter().collect()
     }
 }
 
-fn tree_node<'n, L: Language + 'n>(elt: &SyntaxNode<L>) -> TreeNode<L> {
-    TreeNode(
-        TreeNodeKind::Node(discriminant(&elt.kind())),
-        elt.children_with_tokens()
-            .map(|c| tree_element(&c))
-            .collect::<Vec<_>>(),
-    )
+fn tree_node<'n, L: Language + 'n>(elt: &SyntaxNode<L>, config: &DiffConfig<L>) -> TreeNode<L> {
+    let children = elt
+        .children_with_tokens()
+        .map(|c| tree_element(&c, config))
+        .collect::<Vec<_>>();
+    let subtree_size = 1 + children.iter().map(|c| c.3).sum::<u32>();
+    let weight = (config.node_weight)(elt.kind(), subtree_size);
+    TreeNode(TreeNodeKind::Node(discriminant(&elt.kind())), children, weight, subtree_size)
 }
 
-fn tree_element<'n, L: Language + 'n>(elt: &SyntaxElement<L>) -> TreeNode<L> {
+fn tree_element<'n, L: Language + 'n>(elt: &SyntaxElement<L>, config: &DiffConfig<L>) -> TreeNode<L> {
     match elt {
-        NodeOrToken::Node(node) => tree_node(node),
-        NodeOrToken::Token(token) => TreeNode(TreeNodeKind::Token(token.to_string()), vec![]),
+        NodeOrToken::Node(node) => tree_node(node, config),
+        NodeOrToken::Token(token) => {
+            let text = token.to_string();
+            let weight = (config.token_weight)(&text);
+            TreeNode(TreeNodeKind::Token(text), vec![], weight, 1)
+        }
     }
 }
 
-pub fn edits<'n, L>(from: &'n SyntaxNode<L>, to: &'n SyntaxNode<L>) -> Vec<TreeEdit>
+pub fn edits<'n, L>(from: &'n SyntaxNode<L>, to: &'n SyntaxNode<L>, config: &DiffConfig<L>) -> Vec<TreeEdit>
 where
     L: Language,
     L: 'static,
 {
-    let (edits, _) = tree_edit_distance::diff(&tree_node(from), &tree_node(to));
-    generate_edit(&edits)
+    vec![node_diff(&from.clone().into(), &to.clone().into(), config)]
+}
+
+/// Whether `a` and `b` are interchangeable as-is, i.e. editing one into the other is a no-op.
+///
+/// Nodes compare by green node content (rowan green nodes hash/compare by content, so this is
+/// true for equal subtrees even when `a` and `b` come from different trees), tokens by kind and
+/// text.
+fn elements_match<L: Language>(a: &SyntaxElement<L>, b: &SyntaxElement<L>) -> bool {
+    match (a, b) {
+        (NodeOrToken::Node(a), NodeOrToken::Node(b)) => a.kind() == b.kind() && a.green() == b.green(),
+        (NodeOrToken::Token(a), NodeOrToken::Token(b)) => a.kind() == b.kind() && a.text() == b.text(),
+        _ => false,
+    }
+}
+
+/// Diffs a single `from`/`to` pair, short-circuiting on green-node equality before falling back
+/// to the full edit-distance machinery.
+fn node_diff<L: Language + 'static>(
+    from: &SyntaxElement<L>,
+    to: &SyntaxElement<L>,
+    config: &DiffConfig<L>,
+) -> TreeEdit {
+    if elements_match(from, to) {
+        return TreeEdit::Same;
+    }
+    match (from, to) {
+        (NodeOrToken::Node(from_node), NodeOrToken::Node(to_node)) if from_node.kind() == to_node.kind() => {
+            let from_children = from_node.children_with_tokens().collect_vec();
+            let to_children = to_node.children_with_tokens().collect_vec();
+            let marker = discriminant(&from_node.kind());
+            let child_edits = diff_children(&from_children, &to_children, marker, config);
+            if child_edits.is_empty() || child_edits.iter().all(|e| matches!(e, TreeEdit::Same)) {
+                TreeEdit::Same
+            } else {
+                TreeEdit::Replace(child_edits)
+            }
+        }
+        // Different kinds (or a node facing a token): there is nothing to prune, so fall back
+        // to the plain edit-distance routine for this single pairing.
+        _ => {
+            let (raw, _) = tree_edit_distance::diff(&tree_element(from, config), &tree_element(to, config));
+            generate_edit(&raw)
+                .into_iter()
+                .next()
+                .unwrap_or(TreeEdit::RemoveInsert)
+        }
+    }
+}
+
+/// Diffs two children lists, trimming a matching (by [`elements_match`]) prefix and suffix up
+/// front and only running `tree_edit_distance` on the contiguous middle span that actually
+/// differs. Matched pairs found inside that middle span are re-diffed through [`node_diff`]
+/// rather than trusted as-is, so pruning applies recursively at every depth, not just this one.
+fn diff_children<L: Language + 'static>(
+    from_children: &[SyntaxElement<L>],
+    to_children: &[SyntaxElement<L>],
+    marker: Discriminant<L::Kind>,
+    config: &DiffConfig<L>,
+) -> Vec<TreeEdit> {
+    let prefix = from_children
+        .iter()
+        .zip(to_children.iter())
+        .take_while(|(a, b)| elements_match(a, b))
+        .count();
+    let rest_from = &from_children[prefix..];
+    let rest_to = &to_children[prefix..];
+    let suffix = rest_from
+        .iter()
+        .rev()
+        .zip(rest_to.iter().rev())
+        .take_while(|(a, b)| elements_match(a, b))
+        .count();
+    let from_mid = &rest_from[..rest_from.len() - suffix];
+    let to_mid = &rest_to[..rest_to.len() - suffix];
+
+    let mut mid_edits = match (from_mid.is_empty(), to_mid.is_empty()) {
+        (true, true) => Vec::new(),
+        (true, false) => vec![TreeEdit::Insert(to_mid.len())],
+        (false, true) => vec![TreeEdit::Remove; from_mid.len()],
+        (false, false) => {
+            let from_elements = from_mid.iter().map(|e| tree_element(e, config)).collect_vec();
+            let from_weight = from_elements.iter().map(|c| c.2).sum::<u32>();
+            let from_size = 1 + from_elements.iter().map(|c| c.3).sum::<u32>();
+            let to_elements = to_mid.iter().map(|e| tree_element(e, config)).collect_vec();
+            let to_weight = to_elements.iter().map(|c| c.2).sum::<u32>();
+            let to_size = 1 + to_elements.iter().map(|c| c.3).sum::<u32>();
+            let from_node = TreeNode(TreeNodeKind::Node(marker), from_elements, from_weight, from_size);
+            let to_node = TreeNode(TreeNodeKind::Node(marker), to_elements, to_weight, to_size);
+            let (raw, _) = tree_edit_distance::diff(&from_node, &to_node);
+            let middle = match generate_edit(&raw).into_iter().next() {
+                Some(TreeEdit::Replace(ledits)) => ledits,
+                // `tree_edit_distance` picked something other than replacing this whole span in
+                // place (e.g. a tie between `Replace` and `Remove`+`Insert` when every weight in
+                // `from_mid`/`to_mid` is zero). We haven't actually verified the span is
+                // unchanged, so don't claim `Same` — fall back to removing all of `from_mid` and
+                // inserting all of `to_mid` instead.
+                _ => {
+                    let mut edits = vec![TreeEdit::Remove; from_mid.len()];
+                    edits.push(TreeEdit::Insert(to_mid.len()));
+                    edits
+                }
+            };
+            refine_middle(middle, from_mid, to_mid, config)
+        }
+    };
+
+    // `generate_edit` marks the first edit of whatever sequence it is handed as `InsertFirst`;
+    // re-anchor that to a regular `Insert` (or vice versa) now that the middle is being spliced
+    // into a larger sequence rather than starting one.
+    if let Some(first) = mid_edits.first_mut() {
+        match (prefix, &*first) {
+            (0, TreeEdit::Insert(i)) => *first = TreeEdit::InsertFirst(*i),
+            (p, TreeEdit::InsertFirst(i)) if p > 0 => *first = TreeEdit::Insert(*i),
+            _ => {}
+        }
+    }
+
+    let mut result = Vec::with_capacity(prefix + mid_edits.len() + suffix);
+    result.extend(std::iter::repeat_n(TreeEdit::Same, prefix));
+    result.extend(mid_edits);
+    result.extend(std::iter::repeat_n(TreeEdit::Same, suffix));
+    result
+}
+
+/// Re-diffs every `Replace` entry produced for the middle span through [`node_diff`], so a
+/// matched pair's own children get the same prefix/suffix pruning and an unchanged pair
+/// collapses to `Same`. The edit-distance run over the middle span has already recursed into
+/// these pairs; this refines the resulting edits rather than avoiding that work.
+fn refine_middle<L: Language + 'static>(
+    mid_edits: Vec<TreeEdit>,
+    from_mid: &[SyntaxElement<L>],
+    to_mid: &[SyntaxElement<L>],
+    config: &DiffConfig<L>,
+) -> Vec<TreeEdit> {
+    let mut from_iter = from_mid.iter();
+    let mut to_iter = to_mid.iter();
+    mid_edits
+        .into_iter()
+        .map(|edit| match edit {
+            TreeEdit::Same => {
+                from_iter.next();
+                to_iter.next();
+                TreeEdit::Same
+            }
+            TreeEdit::RemoveInsert => {
+                from_iter.next();
+                to_iter.next();
+                TreeEdit::RemoveInsert
+            }
+            TreeEdit::Remove => {
+                from_iter.next();
+                TreeEdit::Remove
+            }
+            TreeEdit::Insert(n) => {
+                for _ in 0..n {
+                    to_iter.next();
+                }
+                TreeEdit::Insert(n)
+            }
+            TreeEdit::InsertFirst(n) => {
+                for _ in 0..n {
+                    to_iter.next();
+                }
+                TreeEdit::InsertFirst(n)
+            }
+            TreeEdit::Replace(_) => {
+                let from_elt = from_iter.next().unwrap();
+                let to_elt = to_iter.next().unwrap();
+                node_diff(from_elt, to_elt, config)
+            }
+        })
+        .collect()
 }
 
 pub fn generate_edit(edits: &[Edit]) -> Vec<TreeEdit> {